@@ -0,0 +1,171 @@
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+/// Maximum accepted frame payload size, in bytes.
+///
+/// Caps the allocation we're willing to make for a single frame so a peer
+/// can't force an out-of-memory condition by claiming a huge length prefix.
+pub const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+/// Maximum accepted line length, in bytes, for line-oriented mode.
+///
+/// Same rationale as `MAX_FRAME_LEN`: without a cap, a peer that never
+/// sends a newline can make `read_line` grow its buffer without bound.
+pub const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// Reads one length-prefixed frame: a 4-byte big-endian length header
+/// followed by exactly that many payload bytes.
+///
+/// Returns `Ok(None)` if the peer closed the connection cleanly, whether
+/// that happens between frames (EOF on the header) or mid-frame (EOF while
+/// reading the payload) — both are treated as a clean close rather than a
+/// read error. Returns an error for an over-limit length or any other I/O
+/// failure.
+pub async fn read_frame<R: AsyncReadExt + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0u8; 4];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_be_bytes(header);
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame length {len} exceeds maximum of {MAX_FRAME_LEN}"),
+        ));
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    match reader.read_exact(&mut payload).await {
+        Ok(_) => Ok(Some(payload)),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads one line into `line` (cleared first), enforcing `MAX_LINE_LEN`.
+///
+/// Reads raw bytes rather than validating UTF-8 chunk-by-chunk, since a
+/// multi-byte character can straddle two `fill_buf` chunks; callers that
+/// need text can validate the completed line themselves.
+///
+/// Returns `Ok(0)` on a clean EOF with no partial line pending, `Ok(n)` for
+/// the number of bytes read including the trailing newline (if any), and an
+/// error if the line exceeds `MAX_LINE_LEN` before a newline is found.
+pub async fn read_line_bounded<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+) -> io::Result<usize> {
+    line.clear();
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            return Ok(line.len());
+        }
+
+        let (chunk, found_newline) = match available.iter().position(|&b| b == b'\n') {
+            Some(pos) => (&available[..=pos], true),
+            None => (available, false),
+        };
+
+        if line.len() + chunk.len() > MAX_LINE_LEN {
+            let consumed = chunk.len();
+            reader.consume(consumed);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line exceeds maximum length of {MAX_LINE_LEN} bytes"),
+            ));
+        }
+
+        line.extend_from_slice(chunk);
+        let consumed = chunk.len();
+        reader.consume(consumed);
+
+        if found_newline {
+            return Ok(line.len());
+        }
+    }
+}
+
+/// Writes `payload` as a single length-prefixed frame.
+pub async fn write_frame<W: AsyncWriteExt + Unpin>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "payload too large to frame"))?;
+    writer.write_all(&len.to_be_bytes()).await?;
+    writer.write_all(payload).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::BufReader;
+
+    #[tokio::test]
+    async fn read_frame_round_trips_a_payload() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, b"hello").await.unwrap();
+
+        let frame = read_frame(&mut buf.as_slice()).await.unwrap();
+        assert_eq!(frame, Some(b"hello".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn read_frame_returns_none_on_header_eof() {
+        let mut empty: &[u8] = &[];
+        let frame = read_frame(&mut empty).await.unwrap();
+        assert_eq!(frame, None);
+    }
+
+    #[tokio::test]
+    async fn read_frame_returns_none_on_mid_payload_eof() {
+        // Header claims 5 bytes, but the peer only sends 2 before closing.
+        let mut truncated: &[u8] = &[0, 0, 0, 5, b'h', b'i'];
+        let frame = read_frame(&mut truncated).await.unwrap();
+        assert_eq!(frame, None);
+    }
+
+    #[tokio::test]
+    async fn read_frame_rejects_over_limit_length() {
+        let mut header = (MAX_FRAME_LEN + 1).to_be_bytes().to_vec();
+        header.extend_from_slice(b"irrelevant");
+        let mut reader: &[u8] = &header;
+
+        let err = read_frame(&mut reader).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn read_line_bounded_stops_after_inclusive_newline() {
+        let mut reader = BufReader::new(b"abc\ndef".as_slice());
+        let mut line = Vec::new();
+
+        read_line_bounded(&mut reader, &mut line).await.unwrap();
+        assert_eq!(line, b"abc\n");
+
+        read_line_bounded(&mut reader, &mut line).await.unwrap();
+        assert_eq!(line, b"def");
+    }
+
+    #[tokio::test]
+    async fn read_line_bounded_accepts_a_line_exactly_at_the_limit() {
+        let mut payload = vec![b'a'; MAX_LINE_LEN - 1];
+        payload.push(b'\n');
+        let mut reader = BufReader::new(payload.as_slice());
+        let mut line = Vec::new();
+
+        let n = read_line_bounded(&mut reader, &mut line).await.unwrap();
+        assert_eq!(n, MAX_LINE_LEN);
+    }
+
+    #[tokio::test]
+    async fn read_line_bounded_rejects_a_line_over_the_limit() {
+        let payload = vec![b'a'; MAX_LINE_LEN + 1];
+        let mut reader = BufReader::new(payload.as_slice());
+        let mut line = Vec::new();
+
+        let err = read_line_bounded(&mut reader, &mut line).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,21 +1,170 @@
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+mod framing;
 
-fn handle(mut stream: TcpStream) {
-    println!("Connection opened: {}", stream.peer_addr().unwrap());
-    let mut buf = [0; 512];
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+/// Default ceiling on the number of echo connections served concurrently.
+///
+/// Once this many connections are in flight, `run` drops the newest
+/// incoming connection (logging it) instead of spawning an unbounded
+/// number of tasks, and keeps accepting after that.
+const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// How long `run` waits for in-flight connections to finish after a
+/// shutdown signal before aborting whatever is left.
+const DRAIN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// A length-prefixed echo server.
+#[derive(Parser, Debug)]
+#[command(about)]
+struct Cli {
+    /// Address to bind and listen on.
+    #[arg(long, default_value = "0.0.0.0:8080")]
+    bind: String,
+
+    /// Drop a connection if no frame (or, in line mode, no line) is read
+    /// within this many seconds.
+    ///
+    /// Disabled (connections never time out) if unset.
+    #[arg(long)]
+    idle_timeout: Option<u64>,
+
+    /// Switch to line-oriented echo mode: read and echo one line at a time,
+    /// closing the session on a `BYE` line. Defaults to raw framed mode.
+    #[arg(long)]
+    line_mode: bool,
+
+    /// Maximum number of echo connections served concurrently. Once this
+    /// many are in flight, new connections are closed right after being
+    /// accepted, and logged, rather than served.
+    #[arg(long, default_value_t = DEFAULT_MAX_CONNECTIONS)]
+    max_connections: usize,
+}
+
+/// Sentinel line that ends a line-mode session when sent by the client.
+const QUIT_LINE: &[u8] = b"BYE";
+
+/// Strips a trailing `\n` or `\r\n` from `line`, if present.
+fn strip_line_ending(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n")
+        .map_or(line, |l| l.strip_suffix(b"\r").unwrap_or(l))
+}
+
+/// Reads one frame, treating an elapsed `idle_timeout` as an I/O error.
+async fn read_frame_with_idle_timeout(
+    stream: &mut TcpStream,
+    idle_timeout: Option<Duration>,
+) -> io::Result<Option<Vec<u8>>> {
+    match idle_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, framing::read_frame(stream))
+            .await
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "idle timeout"))),
+        None => framing::read_frame(stream).await,
+    }
+}
+
+async fn handle(mut stream: TcpStream, idle_timeout: Option<Duration>, shutdown: CancellationToken) {
+    let peer = stream.peer_addr().unwrap();
+    println!("Connection opened: {peer}");
     loop {
-        match stream.read(&mut buf) {
+        let result = tokio::select! {
+            _ = shutdown.cancelled() => {
+                println!("Shutting down, closing connection: {peer}");
+                let _ = stream.shutdown().await;
+                return;
+            }
+            result = read_frame_with_idle_timeout(&mut stream, idle_timeout) => result,
+        };
+
+        match result {
+            Ok(None) => {
+                println!("Connection closed: {peer}");
+                let _ = stream.shutdown().await;
+                return;
+            }
+            Ok(Some(payload)) => {
+                if let Err(e) = framing::write_frame(&mut stream, &payload).await {
+                    eprintln!("Failed to write to socket: {}", e);
+                    return;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                println!("Connection idle too long: {peer}");
+                let _ = stream.shutdown().await;
+                return;
+            }
+            Err(e) => {
+                eprintln!("Failed to read from socket: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// Reads one line, treating an elapsed `idle_timeout` as an I/O error.
+async fn read_line_with_idle_timeout<R: tokio::io::AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+    idle_timeout: Option<Duration>,
+) -> io::Result<usize> {
+    match idle_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, framing::read_line_bounded(reader, line))
+            .await
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "idle timeout"))),
+        None => framing::read_line_bounded(reader, line).await,
+    }
+}
+
+/// Line-oriented echo mode: buffers reads and echoes a line at a time,
+/// ending the session when the client sends the `BYE` sentinel line.
+async fn handle_lines(stream: TcpStream, idle_timeout: Option<Duration>, shutdown: CancellationToken) {
+    let peer = stream.peer_addr().unwrap();
+    println!("Connection opened: {peer}");
+
+    let (read_half, mut write_half) = stream.into_split();
+    let mut reader = BufReader::new(read_half);
+    let mut line = Vec::new();
+
+    loop {
+        let result = tokio::select! {
+            _ = shutdown.cancelled() => {
+                println!("Shutting down, closing connection: {peer}");
+                let _ = write_half.shutdown().await;
+                return;
+            }
+            result = read_line_with_idle_timeout(&mut reader, &mut line, idle_timeout) => result,
+        };
+
+        match result {
             Ok(0) => {
-                println!("Connection closed: {}", stream.peer_addr().unwrap());
+                println!("Connection closed: {peer}");
+                let _ = write_half.shutdown().await;
                 return;
             }
-            Ok(n) => {
-                if let Err(e) = stream.write_all(&buf[..n]) {
+            Ok(_) => {
+                if strip_line_ending(&line) == QUIT_LINE {
+                    println!("Client said BYE, closing: {peer}");
+                    let _ = write_half.shutdown().await;
+                    return;
+                }
+                if let Err(e) = write_half.write_all(&line).await {
                     eprintln!("Failed to write to socket: {}", e);
                     return;
                 }
             }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                println!("Connection idle too long: {peer}");
+                let _ = write_half.shutdown().await;
+                return;
+            }
             Err(e) => {
                 eprintln!("Failed to read from socket: {}", e);
                 break;
@@ -24,17 +173,119 @@ fn handle(mut stream: TcpStream) {
     }
 }
 
-fn main() -> std::io::Result<()> {
-    let listener = TcpListener::bind("0.0.0.0:8080")?;
-    println!("Server listening on 0.0.0.0:8080");
+/// Installs SIGINT/SIGTERM handlers that cancel `token` on the first signal.
+fn spawn_shutdown_listener(token: CancellationToken) {
+    tokio::spawn(async move {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => println!("Received SIGINT, shutting down"),
+            _ = sigterm.recv() => println!("Received SIGTERM, shutting down"),
+        }
+        token.cancel();
+    });
+}
+
+/// Waits for `connections` to finish, aborting whatever remains once
+/// `grace` has elapsed since this function started.
+async fn drain(connections: &mut JoinSet<()>, grace: Duration) {
+    let deadline = tokio::time::sleep(grace);
+    tokio::pin!(deadline);
+
+    loop {
+        tokio::select! {
+            _ = &mut deadline => {
+                if !connections.is_empty() {
+                    println!(
+                        "Drain grace period elapsed, aborting {} in-flight connection(s)",
+                        connections.len()
+                    );
+                }
+                connections.shutdown().await;
+                return;
+            }
+            next = connections.join_next() => {
+                if next.is_none() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+async fn run(
+    bind_addr: &str,
+    max_connections: usize,
+    idle_timeout: Option<Duration>,
+    line_mode: bool,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(bind_addr).await?;
+    println!("Server listening on {bind_addr}");
+
+    let shutdown = CancellationToken::new();
+    spawn_shutdown_listener(shutdown.clone());
+
+    let pool = Arc::new(Semaphore::new(max_connections));
+    let mut connections = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => {
+                println!("No longer accepting new connections, draining {} in-flight", connections.len());
+                break;
+            }
+            // Reap finished connections so `connections` only holds in-flight ones.
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                let permit = match pool.clone().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        println!("Connection pool full ({max_connections}), dropping new connection");
+                        continue;
+                    }
+                };
+                let conn_shutdown = shutdown.clone();
+                connections.spawn(async move {
+                    if line_mode {
+                        handle_lines(stream, idle_timeout, conn_shutdown).await;
+                    } else {
+                        handle(stream, idle_timeout, conn_shutdown).await;
+                    }
+                    drop(permit);
+                });
+            }
+        }
+    }
+
+    drain(&mut connections, DRAIN_GRACE_PERIOD).await;
+    println!("Shutdown complete");
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    let idle_timeout = cli.idle_timeout.map(Duration::from_secs);
+    run(&cli.bind, cli.max_connections, idle_timeout, cli.line_mode).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    listener
-        .incoming()
-        .try_for_each(|stream| -> Result<_, std::io::Error> {
-            let stream = stream?;
-            std::thread::spawn(move || {
-                handle(stream);
-            });
-            Ok(())
-        })
+    #[test]
+    fn strip_line_ending_strips_lf() {
+        assert_eq!(strip_line_ending(b"BYE\n"), b"BYE");
+    }
+
+    #[test]
+    fn strip_line_ending_strips_crlf() {
+        assert_eq!(strip_line_ending(b"BYE\r\n"), b"BYE");
+    }
+
+    #[test]
+    fn strip_line_ending_leaves_unterminated_line_untouched() {
+        assert_eq!(strip_line_ending(b"BYE"), b"BYE");
+    }
 }